@@ -4,6 +4,11 @@ use crate::bt_client::peer::Peer;
 use crate::bt_client::piece_queue::PieceQueue;
 use crate::errors::*;
 use crate::event_messages::*;
+use crate::p2p_messages::choke::ChokeMsg;
+use crate::p2p_messages::extension::handshake::ExtendedHandshake;
+use crate::p2p_messages::extension::ut_metadata::{MetadataAssembler, UtMetadataMsg};
+use crate::p2p_messages::extension::ut_pex::{PexMsg, MAX_PEERS_PER_MSG};
+use crate::p2p_messages::extension::{ExtendedMsg, EXTENDED_HANDSHAKE_ID};
 use crate::p2p_messages::handshake::Handshake;
 use crate::p2p_messages::interested::InterestedMsg;
 use crate::p2p_messages::keep_alive::KeepAliveMsg;
@@ -12,16 +17,18 @@ use crate::p2p_messages::message_builder::P2PMessage;
 use crate::p2p_messages::message_trait::Message;
 use crate::p2p_messages::piece::PieceMsg;
 use crate::p2p_messages::request::RequestMsg;
+use crate::p2p_messages::unchoke::UnchokeMsg;
 use crate::piece::Piece;
 
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{Ipv4Addr, TcpStream};
 use std::path::Path;
 use std::sync::mpsc::SendError;
 use std::sync::{mpsc::Sender, Arc, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec;
 
 /// # struct PeerConnection (client)
@@ -39,6 +46,15 @@ use std::vec;
 ///     - status 1: downloading  -> waiting piece message
 ///              0: not downloading -> we've received all requested blocks and can request the next one.
 ///     - piece: downloaded piece
+///     - open_requests: number of RequestMsgs sent for the current piece that have not been
+///       answered yet (requested but not yet received)
+///     - max_open_requests: the largest amount of outstanding requests we allow at once, to keep
+///       the connection saturated without flooding the peer's send buffer
+///     - outstanding_requests: which block offsets those open_requests actually are, so blocks
+///       can be accepted out of order and a request stuck too long can be re-sent
+///     - peer_choked (1: we are choking the peer, 0: we are serving it)
+///     - peer_interested (1: the peer wants pieces from us, 0: it doesn't)
+///     - uploaded_bytes: total bytes served to this peer
 #[derive(Debug)]
 pub struct PeerConnection {
     stream: TcpStream,
@@ -46,9 +62,75 @@ pub struct PeerConnection {
     peer: Peer,
     am_choked: bool,
     am_interested: bool,
+    peer_choked: bool,
+    peer_interested: bool,
+    uploaded_bytes: u64,
     pieces: PieceBitfield,
     piece_queue: Arc<RwLock<PieceQueue>>,
     tx_client: Sender<NewEvent>,
+    open_requests: u32,
+    max_open_requests: u32,
+    /// Offset (within the current piece) of every block we've requested but not yet received,
+    /// mapped to when we sent that request. Lets `handle_piece_msg` accept a block by its
+    /// actual offset instead of assuming the peer answers in request order, and lets
+    /// `request_a_piece` re-send a request that's been outstanding too long.
+    outstanding_requests: HashMap<u32, Instant>,
+    reconnect_attempts: u32,
+    backoff: Duration,
+    /// The peer's extended handshake (BEP 10), once negotiated. `None` if the peer doesn't
+    /// support extensions or we haven't heard back yet.
+    peer_extensions: Option<ExtendedHandshake>,
+    /// Present while we're bootstrapping from a magnet link and still missing the torrent's
+    /// info dictionary.
+    metadata_assembler: Option<MetadataAssembler>,
+    /// Peers known to the client across the whole swarm, shared so PEX can gossip newly
+    /// discovered ones and so inbound PEX peers can be folded back in.
+    known_peers: Arc<RwLock<Vec<Peer>>>,
+    /// Shared count of how many known peers have each piece, indexed by piece index. Feeds
+    /// rarest-first selection in `PieceQueue::get_next_piece`.
+    availability: Arc<RwLock<Vec<u16>>>,
+    /// Piece indices already credited to the shared `availability` counter for this peer, so
+    /// a repeated Bitfield/Have can't double-count the same piece.
+    availability_credited: HashSet<u32>,
+    /// Peers we've already advertised to this connection via PEX, so we only ever send the
+    /// `added` diff instead of the whole swarm on every tick.
+    pex_announced: HashSet<(Ipv4Addr, u16)>,
+    last_pex_sent: Instant,
+}
+
+/// Default number of outstanding (requested-but-not-yet-received) block requests kept in
+/// flight per connection.
+const DEFAULT_MAX_OPEN_REQUESTS: u32 = 8;
+
+/// How long a single block request is allowed to sit unanswered before `request_a_piece`
+/// re-sends it. A real seeder interleaving replies across our open window is expected and
+/// fine; this only reaps a slot that looks genuinely stuck.
+const REQUEST_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first reconnect attempt after a dropped connection.
+const INITIAL_BACKOFF_SECS: u64 = 2;
+/// Upper bound the backoff delay is capped at once it has doubled enough times.
+const MAX_BACKOFF_SECS: u64 = 60;
+/// How many times a peer is retried before it is dropped for good.
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+
+/// How often we gossip newly discovered peers to a connection over `ut_pex`.
+const PEX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The standard block size the protocol requests in, matching `Piece::next_block_length`.
+/// Inbound `Request` messages asking for more than this are refused rather than honored, so
+/// an unchoked peer can't force an arbitrarily large allocation/read per request.
+const MAX_BLOCK_LENGTH: u32 = 16 * 1024;
+
+/// Lifecycle status of a `PeerConnection`, reported to the client so the UI can show which
+/// peers are live, backing off, or dead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerStatus {
+    Connecting,
+    Connected,
+    Choked,
+    Disconnected,
+    Backoff(Duration),
 }
 
 impl PeerConnection {
@@ -60,6 +142,8 @@ impl PeerConnection {
         peer: Peer,
         piece_queue: Arc<RwLock<PieceQueue>>,
         tx_client: Sender<NewEvent>,
+        known_peers: Arc<RwLock<Vec<Peer>>>,
+        availability: Arc<RwLock<Vec<u16>>>,
     ) -> Result<PeerConnection, ClientError> {
         if let Ok(stream) = peer.connect() {
             let number_of_pieces = client.get_torrent_info().get_n_pieces();
@@ -71,9 +155,24 @@ impl PeerConnection {
                     peer,
                     am_choked: true,
                     am_interested: false,
+                    peer_choked: true,
+                    peer_interested: false,
+                    uploaded_bytes: 0,
                     pieces: PieceBitfield::new_from_vec(bitfield, number_of_pieces),
                     piece_queue,
                     tx_client,
+                    open_requests: 0,
+                    max_open_requests: DEFAULT_MAX_OPEN_REQUESTS,
+                    outstanding_requests: HashMap::new(),
+                    reconnect_attempts: 0,
+                    backoff: Duration::from_secs(INITIAL_BACKOFF_SECS),
+                    peer_extensions: None,
+                    metadata_assembler: None,
+                    known_peers,
+                    availability,
+                    availability_credited: HashSet::new(),
+                    pex_announced: HashSet::new(),
+                    last_pex_sent: Instant::now(),
                 });
             }
         }
@@ -88,6 +187,9 @@ impl PeerConnection {
             if let Ok(handshake_res) = Handshake::read_msg(&mut self.stream) {
                 if handshake_res.is_valid(self.client.get_torrent_info().get_info_hash()) {
                     self.peer.update_id(handshake_res.get_peer_id());
+                    if handshake_res.supports_extensions() {
+                        self.send_extension_handshake();
+                    }
                     return Ok(());
                 }
             }
@@ -95,6 +197,14 @@ impl PeerConnection {
         Err(DownloadError::HandshakeError)
     }
 
+    /// Sends our BEP 10 extended handshake. The peer's reply (and its inbound requests) come
+    /// back as ordinary `P2PMessage::Extended` messages through the normal receive loop, so
+    /// this doesn't block waiting for one.
+    fn send_extension_handshake(&mut self) {
+        let ours = ExtendedHandshake::new(None);
+        let _ = self.send_message(ours.into_msg());
+    }
+
     fn announce_new_connection(&self) -> Result<(), SendError<NewEvent>> {
         let torrent_name = self.client.get_torrent_info().get_name();
         self.tx_client
@@ -117,6 +227,7 @@ impl PeerConnection {
         if self.exchange_handshake().is_err() || self.announce_new_connection().is_err() {
             return;
         }
+        self.set_status(PeerStatus::Connected);
 
         loop {
             if let Ok(mut piece) = self.fetch_piece() {
@@ -126,10 +237,15 @@ impl PeerConnection {
                     Err(DownloadError::InvalidPiece) => self.return_piece(piece),
 
                     Err(DownloadError::CannotReadPeerMessage) => {
-                        return self.drop_connection(Some(piece));
+                        if !self.try_reconnect(Some(piece), &bf_pieces, &dl_finished) {
+                            return self.drop_connection(None);
+                        }
                     }
                     Err(DownloadError::PeerChokedUs) => {
-                        return self.drop_connection(Some(piece));
+                        self.set_status(PeerStatus::Choked);
+                        if !self.try_reconnect(Some(piece), &bf_pieces, &dl_finished) {
+                            return self.drop_connection(None);
+                        }
                     }
                     _ => (),
                 }
@@ -143,6 +259,77 @@ impl PeerConnection {
         }
     }
 
+    /// Called when the connection to the peer is lost while pieces are still wanted.
+    /// Instead of abandoning the peer outright, it is retried with exponential backoff
+    /// (starting at `INITIAL_BACKOFF_SECS`, doubling up to `MAX_BACKOFF_SECS`), in a plain loop so a
+    /// peer whose link flaps for a long time doesn't grow the call stack one frame per cycle.
+    /// Gives up (returning `false`) once the download finishes, the peer no longer has
+    /// anything we want, or `MAX_RECONNECT_ATTEMPTS` consecutive attempts have failed.
+    /// Returns `true` once a reconnect succeeds, with the connection ready to resume
+    /// `start_download`'s loop.
+    fn try_reconnect(
+        &mut self,
+        curr_piece: Option<Piece>,
+        bf_pieces: &Arc<RwLock<PieceBitfield>>,
+        dl_finished: &Arc<RwLock<bool>>,
+    ) -> bool {
+        if let Some(piece) = curr_piece {
+            self.return_piece(piece);
+        }
+
+        loop {
+            if self.download_finished(dl_finished)
+                || !self.has_any_wanted_piece(bf_pieces)
+                || self.reconnect_attempts >= MAX_RECONNECT_ATTEMPTS
+            {
+                return false;
+            }
+
+            self.set_status(PeerStatus::Backoff(self.backoff));
+            thread::sleep(self.backoff);
+            self.reconnect_attempts += 1;
+            self.backoff = (self.backoff * 2).min(Duration::from_secs(MAX_BACKOFF_SECS));
+
+            self.set_status(PeerStatus::Connecting);
+            if self.reconnect().is_ok() && self.announce_new_connection().is_ok() {
+                self.reconnect_attempts = 0;
+                self.backoff = Duration::from_secs(INITIAL_BACKOFF_SECS);
+                self.set_status(PeerStatus::Connected);
+                return true;
+            }
+        }
+    }
+
+    /// Opens a fresh TCP connection to the peer and redoes the full handshake (BitTorrent and,
+    /// if supported, BEP 10). Resets the choke/interest/extension/PEX state back to
+    /// fresh-connection defaults: the old TCP session is gone and the new peer has no memory of
+    /// it, so that state from before the drop must not carry over. The peer's known pieces, the
+    /// availability credited for them and any metadata fetch in progress are kept.
+    fn reconnect(&mut self) -> Result<(), DownloadError> {
+        let stream = self.peer.connect().map_err(|_| DownloadError::HandshakeError)?;
+        stream
+            .set_read_timeout(Some(Duration::new(5, 0)))
+            .map_err(|_| DownloadError::HandshakeError)?;
+        self.stream = stream;
+
+        self.am_choked = true;
+        self.am_interested = false;
+        self.peer_choked = true;
+        self.peer_interested = false;
+        self.peer_extensions = None;
+        self.pex_announced = HashSet::new();
+        self.last_pex_sent = Instant::now();
+
+        self.exchange_handshake()
+    }
+
+    fn set_status(&self, status: PeerStatus) {
+        let torrent_name = self.client.get_torrent_info().get_name();
+        let _ = self
+            .tx_client
+            .send(NewEvent::PeerStatusChanged(torrent_name, self.peer.clone(), status));
+    }
+
     fn download_finished(&self, dl_pieces: &Arc<RwLock<bool>>) -> bool {
         if let Ok(lock_dl) = dl_pieces.read() {
             return *lock_dl;
@@ -177,6 +364,9 @@ impl PeerConnection {
     }
 
     fn drop_connection(&mut self, curr_piece: Option<Piece>) {
+        self.set_status(PeerStatus::Disconnected);
+        self.release_availability();
+
         if let Some(piece) = curr_piece {
             self.return_piece(piece);
         }
@@ -194,8 +384,12 @@ impl PeerConnection {
     /// -> Note that if the other peer chokes us, the message exchange will end, otherwise,
     /// it will continue until we download the piece or some error arises.
     pub fn download_piece(&mut self, piece: &mut Piece) -> Result<(), DownloadError> {
+        self.open_requests = 0;
+        self.outstanding_requests.clear();
+
         while piece.get_dl() < piece.get_tl() {
             self.keep_connection_alive();
+            self.maybe_send_pex();
 
             if !self.am_interested {
                 self.interested_in_piece();
@@ -204,7 +398,14 @@ impl PeerConnection {
             if !self.am_choked && self.am_interested {
                 self.request_a_piece(piece);
             }
-            self.receive_message(piece)?;
+
+            match self.receive_message(piece) {
+                // The peer is just slow, not gone: the outstanding requests are still good,
+                // so loop back around instead of tearing the connection down.
+                Err(DownloadError::Timeout) => continue,
+                Err(e) => return Err(e),
+                Ok(()) => (),
+            }
         }
         if piece.piece_is_valid() {
             return Ok(());
@@ -214,9 +415,22 @@ impl PeerConnection {
 
     /// Receives a message from the peer.
     /// If the peer choked us, it returns an error because the download has to end.
+    /// If the read simply timed out (the peer is slow, not gone), returns `Timeout` so the
+    /// caller can keep the connection alive instead of dropping it.
     /// Else, it handles the message.
     fn receive_message(&mut self, piece: &mut Piece) -> Result<(), DownloadError> {
-        if let Ok(msg) = MessageBuilder::build(&mut self.stream) {
+        let build_result = MessageBuilder::build(&mut self.stream);
+
+        if let Err(MessageError::ReadingError(io_err)) = &build_result {
+            if matches!(
+                io_err.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ) {
+                return Err(DownloadError::Timeout);
+            }
+        }
+
+        if let Ok(msg) = build_result {
             if let P2PMessage::Choke(_) = msg {
                 self.am_choked = true;
                 return Err(DownloadError::PeerChokedUs);
@@ -234,14 +448,283 @@ impl PeerConnection {
     /// Piece -> handle piece msg
     fn handle_msg(&mut self, message: P2PMessage, piece: &mut Piece) {
         match message {
-            P2PMessage::Bitfield(msg) => self.pieces.add_multiple_pieces(msg.get_pieces()),
-            P2PMessage::Have(msg) => self.pieces.add_a_piece(msg.get_piece_index()),
+            P2PMessage::Bitfield(msg) => {
+                self.pieces.add_multiple_pieces(msg.get_pieces());
+                self.sync_availability_from_bitfield();
+            }
+            P2PMessage::Have(msg) => {
+                self.pieces.add_a_piece(msg.get_piece_index());
+                self.note_piece_available(msg.get_piece_index());
+            }
             P2PMessage::Unchoke(_msg) => self.handle_choke_msg(),
             P2PMessage::Piece(msg) => self.handle_piece_msg(msg, piece),
+            P2PMessage::Interested(_msg) => self.handle_interested_msg(),
+            P2PMessage::NotInterested(_msg) => self.handle_not_interested_msg(),
+            P2PMessage::Request(msg) => self.handle_request_msg(msg),
+            P2PMessage::Extended(msg) => self.handle_extended_msg(msg),
             _ => (),
         }
     }
 
+    /// Dispatches an extension-protocol message by its extended id: id 0 is always the
+    /// extended handshake; any other id is whatever sub-extension we advertised it as.
+    fn handle_extended_msg(&mut self, msg: ExtendedMsg) {
+        if msg.get_extended_id() == EXTENDED_HANDSHAKE_ID {
+            if let Ok(handshake) = ExtendedHandshake::from_payload(&msg.get_payload()) {
+                self.peer_extensions = Some(handshake);
+                self.maybe_bootstrap_metadata();
+            }
+            return;
+        }
+
+        if msg.get_extended_id() == crate::p2p_messages::extension::handshake::UT_METADATA_ID {
+            self.handle_ut_metadata_msg(msg.get_payload());
+            return;
+        }
+
+        if msg.get_extended_id() == crate::p2p_messages::extension::handshake::UT_PEX_ID {
+            self.handle_ut_pex_msg(msg.get_payload());
+        }
+    }
+
+    /// Gossips peers the client has learned about since the last tick to this connection,
+    /// provided it advertised `ut_pex` support and enough time has passed.
+    fn maybe_send_pex(&mut self) {
+        if self.last_pex_sent.elapsed() < PEX_INTERVAL {
+            return;
+        }
+        self.last_pex_sent = Instant::now();
+
+        let Some(peer_extended_id) = self
+            .peer_extensions
+            .as_ref()
+            .and_then(|hs| hs.peer_extended_id("ut_pex"))
+        else {
+            return;
+        };
+
+        let Ok(known_peers) = self.known_peers.read() else {
+            return;
+        };
+
+        let added: Vec<(Ipv4Addr, u16)> = known_peers
+            .iter()
+            .map(|peer| (peer.ip(), peer.port()))
+            .filter(|addr| !self.pex_announced.contains(addr))
+            .take(MAX_PEERS_PER_MSG)
+            .collect();
+        drop(known_peers);
+
+        if added.is_empty() {
+            return;
+        }
+
+        for addr in &added {
+            self.pex_announced.insert(*addr);
+        }
+
+        let msg = PexMsg::new(added, Vec::new()).into_msg(peer_extended_id);
+        let _ = self.send_message(msg);
+    }
+
+    /// Parses an inbound PEX message: prunes `dropped` peers from `known_peers` and forwards
+    /// genuinely new `added` peers to the client. At most `MAX_PEERS_PER_MSG` peers are taken
+    /// from each message, so a single huge `added` list can't flood our peer list; there is no
+    /// running total across messages from the same peer.
+    fn handle_ut_pex_msg(&mut self, payload: Vec<u8>) {
+        let Ok(pex) = PexMsg::from_payload(&payload) else {
+            return;
+        };
+
+        let already_known = |addr: &(Ipv4Addr, u16)| -> bool {
+            self.known_peers
+                .read()
+                .map(|peers| peers.iter().any(|p| (p.ip(), p.port()) == *addr))
+                .unwrap_or(true)
+        };
+
+        let discovered: Vec<Peer> = pex
+            .added
+            .into_iter()
+            .take(MAX_PEERS_PER_MSG)
+            .filter(|addr| !already_known(addr))
+            .map(|(ip, port)| Peer::from_addr(ip, port))
+            .collect();
+
+        if !pex.dropped.is_empty() {
+            if let Ok(mut known_peers) = self.known_peers.write() {
+                known_peers.retain(|p| !pex.dropped.contains(&(p.ip(), p.port())));
+            }
+        }
+
+        if discovered.is_empty() {
+            return;
+        }
+
+        if let Ok(mut known_peers) = self.known_peers.write() {
+            known_peers.extend(discovered.iter().cloned());
+        }
+
+        let torrent_name = self.client.get_torrent_info().get_name();
+        let _ = self
+            .tx_client
+            .send(NewEvent::DiscoveredPeers(torrent_name, discovered));
+    }
+
+    /// If we're still missing the torrent's info dictionary and this peer advertised
+    /// `ut_metadata`, kicks off the BEP 9 metadata exchange by requesting the first piece.
+    fn maybe_bootstrap_metadata(&mut self) {
+        if !self.client.has_torrent_info() {
+            if let Some(peer_extensions) = &self.peer_extensions {
+                if peer_extensions.peer_extended_id("ut_metadata").is_some() {
+                    self.metadata_assembler.get_or_insert_with(MetadataAssembler::new);
+                    self.request_metadata_piece(0);
+                }
+            }
+        }
+    }
+
+    fn request_metadata_piece(&mut self, piece: u32) {
+        if let Some(peer_extended_id) = self
+            .peer_extensions
+            .as_ref()
+            .and_then(|hs| hs.peer_extended_id("ut_metadata"))
+        {
+            let msg = UtMetadataMsg::request(piece).into_msg(peer_extended_id);
+            let _ = self.send_message(msg);
+        }
+    }
+
+    /// Handles an inbound `ut_metadata` message: accumulates Data pieces into the assembler
+    /// and, once the whole info dictionary is in and its SHA-1 matches the info-hash, hands
+    /// the parsed bytes to the Client so normal piece downloading can begin.
+    fn handle_ut_metadata_msg(&mut self, payload: Vec<u8>) {
+        let Ok(msg) = UtMetadataMsg::from_payload(&payload) else {
+            return;
+        };
+
+        match msg {
+            UtMetadataMsg::Data {
+                piece,
+                total_size,
+                block,
+            } => {
+                if let Some(assembler) = &mut self.metadata_assembler {
+                    assembler.add_piece(piece, total_size, block);
+                }
+                self.advance_metadata_fetch();
+            }
+            UtMetadataMsg::Reject { piece } => self.request_metadata_piece(piece),
+            UtMetadataMsg::Request { .. } => (),
+        }
+    }
+
+    /// Drives the metadata fetch one step: requests the next missing piece, or, once every
+    /// piece is in, either hands the validated info dictionary to the Client or, if the
+    /// assembled bytes don't hash to the info-hash, discards them and restarts the fetch from
+    /// scratch instead of leaving the download stuck forever on bad data.
+    fn advance_metadata_fetch(&mut self) {
+        let Some(assembler) = &self.metadata_assembler else {
+            return;
+        };
+
+        if !assembler.is_complete() {
+            if let Some(next) = assembler.next_piece_to_request() {
+                self.request_metadata_piece(next);
+            }
+            return;
+        }
+
+        let info_hash = self.client.get_torrent_info().get_info_hash();
+        match self.metadata_assembler.as_ref().and_then(|a| a.try_finish(&info_hash)) {
+            Some(info_bytes) => {
+                self.client.set_torrent_info_from_metadata(info_bytes);
+                self.metadata_assembler = None;
+            }
+            None => {
+                self.metadata_assembler = None;
+                self.maybe_bootstrap_metadata();
+            }
+        }
+    }
+
+    /// A peer told us it is interested in our pieces. Our choking policy is simple: unchoke
+    /// any interested peer so it can start leeching from us.
+    fn handle_interested_msg(&mut self) {
+        self.peer_interested = true;
+        if self.peer_choked {
+            self.unchoke_peer();
+        }
+    }
+
+    /// A peer is no longer interested in us; there is no point serving it, so choke it back.
+    fn handle_not_interested_msg(&mut self) {
+        self.peer_interested = false;
+        self.choke_peer();
+    }
+
+    fn unchoke_peer(&mut self) {
+        if self.send_message(UnchokeMsg::new()).is_ok() {
+            self.peer_choked = false;
+        }
+    }
+
+    fn choke_peer(&mut self) {
+        if self.send_message(ChokeMsg::new()).is_ok() {
+            self.peer_choked = true;
+        }
+    }
+
+    /// Serves an inbound block request: if the peer is unchoked and interested, asked for a standard-sized
+    /// block, and we have the requested piece stored on disk, reads the block and replies
+    /// with a PieceMsg.
+    fn handle_request_msg(&mut self, msg: RequestMsg) {
+        if self.peer_choked || !self.peer_interested {
+            return;
+        }
+
+        if msg.get_length() == 0 || msg.get_length() > MAX_BLOCK_LENGTH {
+            return;
+        }
+
+        if let Ok(block) =
+            self.read_block_from_disk(msg.get_piece_index(), msg.get_begin(), msg.get_length())
+        {
+            if let Ok(piece_msg) = PieceMsg::new(msg.get_piece_index(), msg.get_begin(), block) {
+                let block_len = piece_msg.get_block().len() as u64;
+                if self.send_message(piece_msg).is_ok() {
+                    self.uploaded_bytes += block_len;
+                    let torrent_name = self.client.get_torrent_info().get_name();
+                    let _ = self.tx_client.send(NewEvent::Uploaded(
+                        torrent_name,
+                        self.uploaded_bytes,
+                        self.peer.clone(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Reads `length` bytes starting at `begin` from the on-disk file of piece `piece_index`.
+    /// Returns an error if we don't have that piece stored yet.
+    fn read_block_from_disk(
+        &self,
+        piece_index: u32,
+        begin: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, ()> {
+        let download_dir_path = self.client.get_download_dir();
+        let torrent_name = self.client.get_torrent_info().get_name();
+        let path = format!("{}/{}_piece_{}", download_dir_path, torrent_name, piece_index);
+
+        let mut file = File::open(path).map_err(|_| ())?;
+        file.seek(SeekFrom::Start(begin as u64)).map_err(|_| ())?;
+
+        let mut block = vec![0u8; length as usize];
+        file.read_exact(&mut block).map_err(|_| ())?;
+        Ok(block)
+    }
+
     fn handle_choke_msg(&mut self) {
         self.am_choked = false;
 
@@ -257,14 +740,24 @@ impl PeerConnection {
         ));
     }
 
-    /// Sets status as NOT_DOWNLOADING (0), the checks if the received block is valid.
-    /// Finally, updates the value of the downloaded byte and appends the received block to self.piece
+    /// Accepts a block if it's actually one we asked for, regardless of whether it's the next
+    /// contiguous offset: peers are free to answer our open request window out of order (e.g.
+    /// a seeder interleaving disk reads), so `outstanding_requests` rather than `piece.get_dl()`
+    /// is what decides acceptance. Frees up the slot it was holding and tops the window back up.
     fn handle_piece_msg(&mut self, msg: PieceMsg, piece: &mut Piece) {
-        if (msg.get_begin() == piece.get_dl()) && (msg.get_piece_index() == piece.get_idx()) {
-            let block = msg.get_block();
-            piece.add_to_dl(block.len() as u32);
-            piece.add_block(block);
+        if msg.get_piece_index() != piece.get_idx() {
+            return;
         }
+
+        if self.outstanding_requests.remove(&msg.get_begin()).is_none() {
+            return;
+        }
+
+        let block = msg.get_block();
+        piece.add_to_dl(block.len() as u32);
+        piece.add_block(msg.get_begin(), block);
+        self.open_requests = self.open_requests.saturating_sub(1);
+        self.request_a_piece(piece);
     }
 
     /// Receives a message and tries to send it to the connected peer.
@@ -308,16 +801,47 @@ impl PeerConnection {
         let _ = self.send_message(keep_alive_msg);
     }
 
-    /// Sends a Request message with the current block and sets status = DOWNLOADING (1)
-    /// If sending failes, returns an error.
+    /// Keeps at most `max_open_requests` RequestMsgs in flight for the current piece.
+    /// First re-sends any request that's been outstanding longer than
+    /// `REQUEST_STALL_TIMEOUT` (the peer may have dropped it silently), then sends as many
+    /// new requests as needed to fill the window, so the number of outstanding
+    /// (requested-but-not-yet-received) blocks stays constant until the tail of the piece.
+    /// Each time a block is accepted in `handle_piece_msg`, this is called again to top the
+    /// window back up.
     fn request_a_piece(&mut self, piece: &mut Piece) {
-        while piece.get_rq() < piece.get_tl() {
+        self.reap_stalled_requests(piece);
+
+        while self.open_requests < self.max_open_requests && piece.get_rq() < piece.get_tl() {
             let begin = piece.get_rq();
             let block_length = piece.next_block_length();
 
             if let Ok(request_msg) = RequestMsg::new(piece.get_idx(), begin, block_length) {
                 if request_msg.send_msg(&mut self.stream).is_ok() {
                     piece.add_to_rq(block_length);
+                    self.outstanding_requests.insert(begin, Instant::now());
+                    self.open_requests += 1;
+                }
+            }
+        }
+    }
+
+    /// Re-sends any outstanding block request older than `REQUEST_STALL_TIMEOUT`, since a
+    /// request the peer never answered (and never will) would otherwise hold its slot forever
+    /// and leave the piece's download permanently short of `get_tl()`.
+    fn reap_stalled_requests(&mut self, piece: &mut Piece) {
+        let stale_offsets: Vec<u32> = self
+            .outstanding_requests
+            .iter()
+            .filter(|(_, requested_at)| requested_at.elapsed() >= REQUEST_STALL_TIMEOUT)
+            .map(|(&begin, _)| begin)
+            .collect();
+
+        for begin in stale_offsets {
+            let block_length = piece.block_length_at(begin);
+
+            if let Ok(request_msg) = RequestMsg::new(piece.get_idx(), begin, block_length) {
+                if request_msg.send_msg(&mut self.stream).is_ok() {
+                    self.outstanding_requests.insert(begin, Instant::now());
                 }
             }
         }
@@ -337,15 +861,57 @@ impl PeerConnection {
     }
 
     fn fetch_piece(&mut self) -> Result<Piece, ()> {
-        if let Ok(mut pq_lock) = self.piece_queue.write() {
-            if let Some(option_piece) = pq_lock.get_next_piece() {
-                return Ok(option_piece);
+        if let Ok(avail) = self.availability.read() {
+            if let Ok(mut pq_lock) = self.piece_queue.write() {
+                if let Some(piece) = pq_lock.get_next_piece(&avail, &self.pieces) {
+                    return Ok(piece);
+                }
             }
         }
 
         Err(())
     }
 
+    /// Bumps the availability count for every piece index this peer's bitfield says it has
+    /// that we haven't already credited to this connection. A well-behaved peer only ever
+    /// sends one Bitfield, but nothing here assumes or enforces that: `note_piece_available`
+    /// is itself idempotent per index, so a duplicate or repeated Bitfield can't double-count.
+    fn sync_availability_from_bitfield(&mut self) {
+        let n_pieces = self.client.get_torrent_info().get_n_pieces();
+        for idx in 0..n_pieces {
+            if self.pieces.has_piece(idx) {
+                self.note_piece_available(idx);
+            }
+        }
+    }
+
+    /// Credits piece `piece_idx` as available from this peer, exactly once per connection: if
+    /// we already credited it (tracked in `availability_credited`), this is a no-op, so
+    /// handling the same Have/Bitfield information twice can't drift the shared counter.
+    fn note_piece_available(&mut self, piece_idx: u32) {
+        if !self.availability_credited.insert(piece_idx) {
+            return;
+        }
+        if let Ok(mut availability) = self.availability.write() {
+            if let Some(count) = availability.get_mut(piece_idx as usize) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Decrements the availability count for every piece actually credited to this
+    /// connection, called when the connection is dropped so availability stays accurate for
+    /// the peers that remain.
+    fn release_availability(&mut self) {
+        if let Ok(mut availability) = self.availability.write() {
+            for idx in self.availability_credited.drain() {
+                if let Some(count) = availability.get_mut(idx as usize) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+        }
+    }
+
     fn return_piece(&mut self, mut piece: Piece) {
         piece.reset_info();
 
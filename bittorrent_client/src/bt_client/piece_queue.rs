@@ -0,0 +1,87 @@
+use crate::bitfield::PieceBitfield;
+use crate::piece::Piece;
+use rand::Rng;
+
+/// # struct PieceQueue (client)
+/// Holds the pieces still wanted from the swarm and hands them out rarest-first: the piece
+/// with the lowest availability (fewest peers known to have it) that the asking peer actually
+/// has is returned first, so rare pieces don't become the download's bottleneck. Ties are
+/// broken randomly so multiple connections picking a piece at the same time don't all converge
+/// on the same one.
+#[derive(Debug)]
+pub struct PieceQueue {
+    pieces: Vec<Piece>,
+}
+
+impl PieceQueue {
+    pub fn new(pieces: Vec<Piece>) -> PieceQueue {
+        PieceQueue { pieces }
+    }
+
+    /// Picks and removes the wanted piece with the lowest availability that `peer_pieces` has.
+    /// `availability` is indexed by piece index and holds how many known peers have each piece.
+    pub fn get_next_piece(
+        &mut self,
+        availability: &[u16],
+        peer_pieces: &PieceBitfield,
+    ) -> Option<Piece> {
+        let candidates: Vec<usize> = self
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| peer_pieces.has_piece(piece.get_idx()))
+            .map(|(i, _)| i)
+            .collect();
+
+        let min_availability = candidates
+            .iter()
+            .map(|&i| Self::availability_of(availability, self.pieces[i].get_idx()))
+            .min()?;
+
+        let rarest: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&i| Self::availability_of(availability, self.pieces[i].get_idx()) == min_availability)
+            .collect();
+
+        let chosen = rarest[rand::thread_rng().gen_range(0..rarest.len())];
+        Some(self.pieces.remove(chosen))
+    }
+
+    fn availability_of(availability: &[u16], piece_idx: u32) -> u16 {
+        availability.get(piece_idx as usize).copied().unwrap_or(0)
+    }
+
+    pub fn push_back(&mut self, piece: Piece) {
+        self.pieces.push(piece);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitfield_with_all_pieces(n_pieces: u32) -> PieceBitfield {
+        let bytes = vec![0xFFu8; (n_pieces as f32 / 8.0).ceil() as usize];
+        PieceBitfield::new_from_vec(bytes, n_pieces)
+    }
+
+    #[test]
+    fn prefers_the_candidate_with_lower_availability() {
+        let pieces = vec![Piece::new(0, 1024), Piece::new(1, 1024), Piece::new(2, 1024)];
+        let mut queue = PieceQueue::new(pieces);
+        // Piece 1 is the rarest (availability 1), so it should be picked over pieces 0 and 2
+        // even though they appear earlier/later in the queue.
+        let availability = vec![5, 1, 3];
+        let peer_pieces = bitfield_with_all_pieces(3);
+
+        let chosen = queue
+            .get_next_piece(&availability, &peer_pieces)
+            .expect("a wanted piece the peer has should be returned");
+
+        assert_eq!(chosen.get_idx(), 1);
+    }
+}
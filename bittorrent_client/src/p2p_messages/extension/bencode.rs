@@ -0,0 +1,182 @@
+//! Minimal bencode support for the small, flat dictionaries used by the extension protocol
+//! (the extended handshake's `m`/`metadata_size` dict, `ut_metadata`'s
+//! `msg_type`/`piece`/`total_size` dict, and `ut_pex`'s `added`/`dropped` byte strings). This
+//! is not a general-purpose bencode implementation: it only encodes/decodes dictionaries
+//! whose values are integers, byte strings, or, one level deep, a nested dictionary of
+//! integers.
+
+use crate::errors::MessageError;
+use std::collections::BTreeMap;
+
+/// Upper bound on a single bencoded byte string's declared length. Nothing this module decodes
+/// (dictionary keys, a `ut_pex` compact peer list) legitimately needs more than a few KiB;
+/// `ut_metadata`'s ~16 KiB block follows the dictionary as raw bytes and is not decoded here.
+/// A declared length beyond this is refused outright, before it's used in any arithmetic or
+/// allocation.
+const MAX_RAW_STRING_LEN: usize = 32 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BenValue {
+    Int(i64),
+    Bytes(Vec<u8>),
+    Dict(BTreeMap<String, i64>),
+}
+
+pub fn encode_dict(dict: &BTreeMap<String, BenValue>) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.push(b'd');
+    for (key, value) in dict {
+        encode_string(key, &mut out);
+        match value {
+            BenValue::Int(n) => encode_int(*n, &mut out),
+            BenValue::Bytes(bytes) => encode_bytes(bytes, &mut out),
+            BenValue::Dict(inner) => {
+                out.push(b'd');
+                for (inner_key, inner_val) in inner {
+                    encode_string(inner_key, &mut out);
+                    encode_int(*inner_val, &mut out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+    out.push(b'e');
+    out
+}
+
+fn encode_string(s: &str, out: &mut Vec<u8>) {
+    encode_bytes(s.as_bytes(), out);
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn encode_int(n: i64, out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("i{}e", n).as_bytes());
+}
+
+pub fn decode_dict(bytes: &[u8]) -> Result<BTreeMap<String, BenValue>, MessageError> {
+    decode_dict_prefix(bytes).map(|(dict, _consumed)| dict)
+}
+
+/// Like `decode_dict`, but also returns how many leading bytes made up the dictionary, so a
+/// caller can find trailing raw data appended after it (as `ut_metadata` data messages do).
+pub fn decode_dict_prefix(
+    bytes: &[u8],
+) -> Result<(BTreeMap<String, BenValue>, usize), MessageError> {
+    let mut cursor = 0usize;
+    expect_byte(bytes, &mut cursor, b'd')?;
+
+    let mut dict = BTreeMap::new();
+    while peek(bytes, cursor)? != b'e' {
+        let key = decode_string(bytes, &mut cursor)?;
+        let value = match peek(bytes, cursor)? {
+            b'i' => BenValue::Int(decode_int(bytes, &mut cursor)?),
+            b'd' => BenValue::Dict(decode_int_dict(bytes, &mut cursor)?),
+            b'0'..=b'9' => BenValue::Bytes(decode_raw_string(bytes, &mut cursor)?),
+            _ => return Err(MessageError::CreationError),
+        };
+        dict.insert(key, value);
+    }
+    cursor += 1;
+    Ok((dict, cursor))
+}
+
+fn decode_int_dict(bytes: &[u8], cursor: &mut usize) -> Result<BTreeMap<String, i64>, MessageError> {
+    expect_byte(bytes, cursor, b'd')?;
+    let mut dict = BTreeMap::new();
+    while peek(bytes, *cursor)? != b'e' {
+        let key = decode_string(bytes, cursor)?;
+        let value = decode_int(bytes, cursor)?;
+        dict.insert(key, value);
+    }
+    *cursor += 1;
+    Ok(dict)
+}
+
+fn peek(bytes: &[u8], cursor: usize) -> Result<u8, MessageError> {
+    bytes.get(cursor).copied().ok_or(MessageError::CreationError)
+}
+
+fn expect_byte(bytes: &[u8], cursor: &mut usize, expected: u8) -> Result<(), MessageError> {
+    if peek(bytes, *cursor)? != expected {
+        return Err(MessageError::CreationError);
+    }
+    *cursor += 1;
+    Ok(())
+}
+
+fn decode_string(bytes: &[u8], cursor: &mut usize) -> Result<String, MessageError> {
+    let raw = decode_raw_string(bytes, cursor)?;
+    String::from_utf8(raw).map_err(|_| MessageError::CreationError)
+}
+
+fn decode_raw_string(bytes: &[u8], cursor: &mut usize) -> Result<Vec<u8>, MessageError> {
+    let colon = bytes[*cursor..]
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(MessageError::CreationError)?;
+    let len: usize = std::str::from_utf8(&bytes[*cursor..*cursor + colon])
+        .map_err(|_| MessageError::CreationError)?
+        .parse()
+        .map_err(|_| MessageError::CreationError)?;
+
+    if len > MAX_RAW_STRING_LEN {
+        return Err(MessageError::CreationError);
+    }
+
+    let start = *cursor + colon + 1;
+    let end = start.checked_add(len).ok_or(MessageError::CreationError)?;
+    let value = bytes
+        .get(start..end)
+        .ok_or(MessageError::CreationError)?
+        .to_vec();
+    *cursor = end;
+
+    Ok(value)
+}
+
+fn decode_int(bytes: &[u8], cursor: &mut usize) -> Result<i64, MessageError> {
+    expect_byte(bytes, cursor, b'i')?;
+    let end = bytes[*cursor..]
+        .iter()
+        .position(|&b| b == b'e')
+        .ok_or(MessageError::CreationError)?;
+    let value = std::str::from_utf8(&bytes[*cursor..*cursor + end])
+        .map_err(|_| MessageError::CreationError)?
+        .parse()
+        .map_err(|_| MessageError::CreationError)?;
+    *cursor += end + 1;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_dict_of_ints_bytes_and_a_nested_int_dict() {
+        let mut inner = BTreeMap::new();
+        inner.insert("ut_metadata".to_string(), 3);
+
+        let mut dict = BTreeMap::new();
+        dict.insert("msg_type".to_string(), BenValue::Int(1));
+        dict.insert("piece".to_string(), BenValue::Bytes(vec![1, 2, 3, 4]));
+        dict.insert("m".to_string(), BenValue::Dict(inner));
+
+        let encoded = encode_dict(&dict);
+        let decoded = decode_dict(&encoded).expect("a dict we just encoded should decode back");
+
+        assert_eq!(decoded, dict);
+    }
+
+    #[test]
+    fn rejects_a_raw_string_length_beyond_the_cap() {
+        let payload = format!("{}:", MAX_RAW_STRING_LEN + 1).into_bytes();
+        let mut cursor = 0usize;
+
+        assert!(decode_raw_string(&payload, &mut cursor).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! The extended handshake (BEP 10, extended id 0): the first message exchanged once both
+//! peers have advertised the extension-protocol bit in their regular Handshake. It carries a
+//! bencoded `m` dictionary mapping extension names to the extended ids the sender wants to
+//! use for them, plus metadata about the sender (here, just `metadata_size`).
+
+use crate::errors::MessageError;
+use crate::p2p_messages::extension::bencode::{decode_dict, encode_dict, BenValue};
+use crate::p2p_messages::extension::{ExtendedMsg, EXTENDED_HANDSHAKE_ID};
+use std::collections::BTreeMap;
+
+/// Extended id we advertise for `ut_metadata` (BEP 9) in our own handshake dict.
+pub const UT_METADATA_ID: u8 = 1;
+/// Extended id we advertise for `ut_pex` in our own handshake dict.
+pub const UT_PEX_ID: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct ExtendedHandshake {
+    supported: BTreeMap<String, i64>,
+    metadata_size: Option<u32>,
+}
+
+impl ExtendedHandshake {
+    /// Builds the handshake we send to every peer that also set the extension bit:
+    /// `ut_metadata` and `ut_pex` are always offered, and `metadata_size` is included once we
+    /// know the size of the bencoded info dictionary (it's absent before we have a torrent).
+    pub fn new(metadata_size: Option<u32>) -> ExtendedHandshake {
+        let mut supported = BTreeMap::new();
+        supported.insert("ut_metadata".to_string(), UT_METADATA_ID as i64);
+        supported.insert("ut_pex".to_string(), UT_PEX_ID as i64);
+        ExtendedHandshake {
+            supported,
+            metadata_size,
+        }
+    }
+
+    /// The extended id the peer wants us to use when sending it `name` messages, if it
+    /// advertised support for that extension.
+    pub fn peer_extended_id(&self, name: &str) -> Option<u8> {
+        self.supported.get(name).map(|id| *id as u8)
+    }
+
+    pub fn metadata_size(&self) -> Option<u32> {
+        self.metadata_size
+    }
+
+    pub fn into_msg(self) -> ExtendedMsg {
+        let mut dict = BTreeMap::new();
+        dict.insert("m".to_string(), BenValue::Dict(self.supported));
+        if let Some(size) = self.metadata_size {
+            dict.insert("metadata_size".to_string(), BenValue::Int(size as i64));
+        }
+        ExtendedMsg::new(EXTENDED_HANDSHAKE_ID, encode_dict(&dict))
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<ExtendedHandshake, MessageError> {
+        let dict = decode_dict(payload)?;
+
+        let supported = match dict.get("m") {
+            Some(BenValue::Dict(inner)) => inner.clone(),
+            _ => BTreeMap::new(),
+        };
+        let metadata_size = match dict.get("metadata_size") {
+            Some(BenValue::Int(n)) => Some(*n as u32),
+            _ => None,
+        };
+
+        Ok(ExtendedHandshake {
+            supported,
+            metadata_size,
+        })
+    }
+}
@@ -0,0 +1,91 @@
+//! BEP 10 extension protocol: a single `P2PMessage::Extended` (id 20) carries an
+//! "extended message id" byte followed by a bencoded payload, used to negotiate and then
+//! speak sub-extensions such as `ut_metadata` (BEP 9) and `ut_pex`.
+
+mod bencode;
+pub mod handshake;
+pub mod ut_metadata;
+pub mod ut_pex;
+
+use crate::errors::MessageError;
+use crate::p2p_messages::message_trait::Message;
+use std::io::{Read, Write};
+
+/// The regular message id every extension-protocol message is sent under.
+pub const EXTENDED_MESSAGE_ID: u8 = 20;
+/// The reserved extended id for the handshake itself (every other id is negotiated in it).
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+/// Upper bound on an Extended message's declared length. The largest legitimate payload we
+/// ever exchange is a `ut_metadata` piece (16 KiB, see `ut_metadata::METADATA_PIECE_SIZE`) plus
+/// its small bencoded envelope; every peer that sets the BEP10 bit in its handshake can reach
+/// this parser, so anything far beyond that is refused before it can force a large allocation.
+const MAX_EXTENDED_MSG_LEN: u32 = 32 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtendedMsg {
+    _length: u32,
+    id: u8,
+    extended_id: u8,
+    payload: Vec<u8>,
+}
+
+impl ExtendedMsg {
+    /// Create and returns an Extended Message.
+    pub fn new(extended_id: u8, payload: Vec<u8>) -> ExtendedMsg {
+        ExtendedMsg {
+            _length: (2 + payload.len()) as u32,
+            id: EXTENDED_MESSAGE_ID,
+            extended_id,
+            payload,
+        }
+    }
+
+    /// Reads an Extended Message from a stream and returns it.
+    pub fn read_msg(length: u32, stream: &mut dyn Read) -> Result<ExtendedMsg, MessageError> {
+        if !(2..=MAX_EXTENDED_MSG_LEN).contains(&length) {
+            return Err(MessageError::CreationError);
+        }
+
+        let mut extended_id = [0u8; 1];
+        stream
+            .read_exact(&mut extended_id)
+            .map_err(MessageError::ReadingError)?;
+
+        let mut payload = vec![0u8; (length - 2) as usize];
+        stream
+            .read_exact(&mut payload)
+            .map_err(MessageError::ReadingError)?;
+
+        Ok(ExtendedMsg::new(extended_id[0], payload))
+    }
+
+    pub fn get_extended_id(&self) -> u8 {
+        self.extended_id
+    }
+
+    pub fn get_payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+impl Message for ExtendedMsg {
+    /// Writes the bytes of an Extended Message in the received stream.
+    fn send_msg(&self, stream: &mut dyn Write) -> Result<(), MessageError> {
+        stream
+            .write_all(&self._length.to_be_bytes())
+            .map_err(MessageError::SendingError)?;
+        stream
+            .write_all(&self.id.to_be_bytes())
+            .map_err(MessageError::SendingError)?;
+        stream
+            .write_all(&self.extended_id.to_be_bytes())
+            .map_err(MessageError::SendingError)?;
+        stream
+            .write_all(&self.payload)
+            .map_err(MessageError::SendingError)?;
+        let _ = stream.flush();
+
+        Ok(())
+    }
+}
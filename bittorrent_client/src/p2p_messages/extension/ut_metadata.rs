@@ -0,0 +1,156 @@
+//! `ut_metadata` (BEP 9): lets a client that only has a magnet-style info-hash fetch the
+//! info dictionary itself from peers, 16 KiB at a time, and verify it against the hash before
+//! trusting it.
+
+use crate::errors::MessageError;
+use crate::p2p_messages::extension::bencode::{decode_dict_prefix, encode_dict, BenValue};
+use crate::p2p_messages::extension::ExtendedMsg;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+
+/// Metadata is always exchanged in 16 KiB pieces, per BEP 9.
+pub const METADATA_PIECE_SIZE: u32 = 16 * 1024;
+
+const MSG_TYPE_REQUEST: i64 = 0;
+const MSG_TYPE_DATA: i64 = 1;
+const MSG_TYPE_REJECT: i64 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UtMetadataMsg {
+    Request { piece: u32 },
+    Data { piece: u32, total_size: u32, block: Vec<u8> },
+    Reject { piece: u32 },
+}
+
+impl UtMetadataMsg {
+    pub fn request(piece: u32) -> UtMetadataMsg {
+        UtMetadataMsg::Request { piece }
+    }
+
+    /// Wraps this message in an `ExtendedMsg` addressed to the peer's negotiated extended id
+    /// for `ut_metadata` (learned from its extended handshake).
+    pub fn into_msg(self, peer_extended_id: u8) -> ExtendedMsg {
+        let mut dict = BTreeMap::new();
+        let mut block = Vec::new();
+
+        match self {
+            UtMetadataMsg::Request { piece } => {
+                dict.insert("msg_type".to_string(), BenValue::Int(MSG_TYPE_REQUEST));
+                dict.insert("piece".to_string(), BenValue::Int(piece as i64));
+            }
+            UtMetadataMsg::Data {
+                piece,
+                total_size,
+                block: data,
+            } => {
+                dict.insert("msg_type".to_string(), BenValue::Int(MSG_TYPE_DATA));
+                dict.insert("piece".to_string(), BenValue::Int(piece as i64));
+                dict.insert("total_size".to_string(), BenValue::Int(total_size as i64));
+                block = data;
+            }
+            UtMetadataMsg::Reject { piece } => {
+                dict.insert("msg_type".to_string(), BenValue::Int(MSG_TYPE_REJECT));
+                dict.insert("piece".to_string(), BenValue::Int(piece as i64));
+            }
+        }
+
+        let mut payload = encode_dict(&dict);
+        payload.extend_from_slice(&block);
+        ExtendedMsg::new(peer_extended_id, payload)
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<UtMetadataMsg, MessageError> {
+        let (dict, consumed) = decode_dict_prefix(payload)?;
+
+        let msg_type = match dict.get("msg_type") {
+            Some(BenValue::Int(n)) => *n,
+            _ => return Err(MessageError::CreationError),
+        };
+        let piece = match dict.get("piece") {
+            Some(BenValue::Int(n)) => *n as u32,
+            _ => return Err(MessageError::CreationError),
+        };
+
+        match msg_type {
+            MSG_TYPE_REQUEST => Ok(UtMetadataMsg::Request { piece }),
+            MSG_TYPE_REJECT => Ok(UtMetadataMsg::Reject { piece }),
+            MSG_TYPE_DATA => {
+                let total_size = match dict.get("total_size") {
+                    Some(BenValue::Int(n)) => *n as u32,
+                    _ => return Err(MessageError::CreationError),
+                };
+                let block = payload
+                    .get(consumed..)
+                    .ok_or(MessageError::CreationError)?
+                    .to_vec();
+                Ok(UtMetadataMsg::Data {
+                    piece,
+                    total_size,
+                    block,
+                })
+            }
+            _ => Err(MessageError::CreationError),
+        }
+    }
+}
+
+/// Reassembles the info dictionary from `ut_metadata` Data messages and validates it against
+/// the torrent's info-hash before anyone is allowed to trust it.
+#[derive(Debug, Default)]
+pub struct MetadataAssembler {
+    total_size: Option<u32>,
+    pieces: BTreeMap<u32, Vec<u8>>,
+}
+
+impl MetadataAssembler {
+    pub fn new() -> MetadataAssembler {
+        MetadataAssembler::default()
+    }
+
+    pub fn next_piece_to_request(&self) -> Option<u32> {
+        let n_pieces = self.expected_piece_count()?;
+        (0..n_pieces).find(|idx| !self.pieces.contains_key(idx))
+    }
+
+    pub fn add_piece(&mut self, piece: u32, total_size: u32, block: Vec<u8>) {
+        self.total_size.get_or_insert(total_size);
+        self.pieces.insert(piece, block);
+    }
+
+    fn expected_piece_count(&self) -> Option<u32> {
+        let total_size = self.total_size?;
+        Some(total_size.div_ceil(METADATA_PIECE_SIZE))
+    }
+
+    /// Whether every expected piece has arrived, regardless of whether the assembled bytes
+    /// turn out to hash correctly. Lets the caller tell "still fetching" apart from "fetched
+    /// everything but it was corrupt/malicious", which `try_finish` alone can't: both return
+    /// `None` from it.
+    pub fn is_complete(&self) -> bool {
+        self.expected_piece_count()
+            .is_some_and(|n_pieces| self.pieces.len() as u32 == n_pieces)
+    }
+
+    /// Once every piece has arrived, concatenates them and checks the SHA-1 against
+    /// `info_hash`. Returns the raw bencoded info dictionary bytes on success so the caller
+    /// can hand them to the torrent parser.
+    pub fn try_finish(&self, info_hash: &[u8]) -> Option<Vec<u8>> {
+        let n_pieces = self.expected_piece_count()?;
+        if self.pieces.len() as u32 != n_pieces {
+            return None;
+        }
+
+        let mut assembled = Vec::new();
+        for idx in 0..n_pieces {
+            assembled.extend_from_slice(self.pieces.get(&idx)?);
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.update(&assembled);
+        if hasher.finalize().as_slice() == info_hash {
+            Some(assembled)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! `ut_pex`: peers gossip other peers to each other over the extended channel so the swarm
+//! can grow without hitting the tracker again. Each message is a bencoded dict with
+//! `added`/`dropped` keys holding compact peer lists (4-byte IPv4 + 2-byte port, back to
+//! back, no separators).
+
+use crate::errors::MessageError;
+use crate::p2p_messages::extension::bencode::{decode_dict, encode_dict, BenValue};
+use crate::p2p_messages::extension::ExtendedMsg;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+/// A single entry of a compact peer list: 4 bytes of IPv4 address + 2 bytes of port.
+const COMPACT_PEER_LEN: usize = 6;
+
+/// A cap on how many peers a single PEX message is allowed to contribute, so one malicious
+/// or buggy peer can't poison our peer list with an enormous `added` list.
+pub const MAX_PEERS_PER_MSG: usize = 50;
+
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct PexMsg {
+    pub added: Vec<(Ipv4Addr, u16)>,
+    pub dropped: Vec<(Ipv4Addr, u16)>,
+}
+
+impl PexMsg {
+    pub fn new(added: Vec<(Ipv4Addr, u16)>, dropped: Vec<(Ipv4Addr, u16)>) -> PexMsg {
+        PexMsg { added, dropped }
+    }
+
+    pub fn into_msg(self, peer_extended_id: u8) -> ExtendedMsg {
+        let mut dict = BTreeMap::new();
+        dict.insert("added".to_string(), BenValue::Bytes(encode_compact(&self.added)));
+        dict.insert(
+            "dropped".to_string(),
+            BenValue::Bytes(encode_compact(&self.dropped)),
+        );
+        ExtendedMsg::new(peer_extended_id, encode_dict(&dict))
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<PexMsg, MessageError> {
+        let dict = decode_dict(payload)?;
+
+        let added = match dict.get("added") {
+            Some(BenValue::Bytes(bytes)) => decode_compact(bytes, MAX_PEERS_PER_MSG),
+            _ => Vec::new(),
+        };
+        let dropped = match dict.get("dropped") {
+            Some(BenValue::Bytes(bytes)) => decode_compact(bytes, MAX_PEERS_PER_MSG),
+            _ => Vec::new(),
+        };
+
+        Ok(PexMsg { added, dropped })
+    }
+}
+
+fn encode_compact(peers: &[(Ipv4Addr, u16)]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(peers.len() * COMPACT_PEER_LEN);
+    for (ip, port) in peers {
+        out.extend_from_slice(&ip.octets());
+        out.extend_from_slice(&port.to_be_bytes());
+    }
+    out
+}
+
+/// Decodes a compact peer list, silently dropping a trailing partial entry and capping the
+/// number of peers a single source may contribute.
+fn decode_compact(bytes: &[u8], max_peers: usize) -> Vec<(Ipv4Addr, u16)> {
+    bytes
+        .chunks_exact(COMPACT_PEER_LEN)
+        .take(max_peers)
+        .map(|chunk| {
+            let ip = Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]);
+            let port = u16::from_be_bytes([chunk[4], chunk[5]]);
+            (ip, port)
+        })
+        .collect()
+}
@@ -4,6 +4,11 @@ use crate::p2p_messages::message_trait::Message;
 
 use std::io::{Read, Write};
 
+/// Byte 5 of the reserved field, bit `0x10`: set when we support the BEP 10 extension
+/// protocol (https://www.bittorrent.org/beps/bep_0010.html).
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Handshake {
     pstrlen: u8,
@@ -14,13 +19,13 @@ pub struct Handshake {
 }
 
 impl Handshake {
-    /// Create and returns a Handshake.
+    /// Create and returns a Handshake. Advertises support for the BEP 10 extension protocol.
     pub fn new(sender: &Client, pstr: &str) -> Handshake {
         let torrent_info = sender.get_torrent_info();
         Handshake {
             pstrlen: pstr.len() as u8,
             pstr: pstr.as_bytes().to_vec(),
-            reserved: vec![0; 8],
+            reserved: Self::reserved_with_extensions(),
             info_hash: torrent_info.get_info_hash(),
             peer_id: sender.get_peer_id(),
         }
@@ -30,12 +35,26 @@ impl Handshake {
         Handshake {
             pstrlen: pstr.len() as u8,
             pstr: pstr.as_bytes().to_vec(),
-            reserved: vec![0; 8],
+            reserved: Self::reserved_with_extensions(),
             info_hash,
             peer_id,
         }
     }
 
+    fn reserved_with_extensions() -> Vec<u8> {
+        let mut reserved = vec![0; 8];
+        reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        reserved
+    }
+
+    /// Whether the peer on the other end of this handshake also set the extension-protocol
+    /// bit, meaning we can follow up with an extended handshake (BEP 10).
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved
+            .get(EXTENSION_PROTOCOL_BYTE)
+            .is_some_and(|byte| byte & EXTENSION_PROTOCOL_BIT != 0)
+    }
+
     /// Reads a Handshake from a stream and returns it.
     pub fn read_msg(stream: &mut dyn Read) -> Result<Handshake, MessageError> {
         let mut pstrlen = [0u8; 1];